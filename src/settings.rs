@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::{Path, PathBuf};
@@ -5,47 +6,141 @@ use std::path::{Path, PathBuf};
 use chrono;
 use hex;
 use serde::{Deserialize, Serialize};
+use serde_json;
+use serde_yaml;
 use sha1::{Digest, Sha1};
 use toml;
+use toml_edit;
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+/// On-disk serialization format of a settings file.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum FileFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl Default for FileFormat {
+    fn default() -> Self {
+        FileFormat::Toml
+    }
+}
+
+impl FileFormat {
+    /// Map a file extension (without the leading dot) to its format, if supported.
+    fn from_extension(extension: &str) -> Option<FileFormat> {
+        match extension {
+            "toml" => Some(FileFormat::Toml),
+            "json" => Some(FileFormat::Json),
+            "yaml" | "yml" => Some(FileFormat::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Canonical file extension used when writing this format back to disk.
+    fn extension(&self) -> &'static str {
+        match self {
+            FileFormat::Toml => "toml",
+            FileFormat::Json => "json",
+            FileFormat::Yaml => "yaml",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct Header {
     pub name: String,
     pub modified: bool,
     pub hash: String, // File's sha1
     pub date: String, // ISO 8601 / RFC 3339 date & time format.
+    #[serde(default)]
+    pub format: FileFormat, // Format the file was loaded from / will be saved as.
+    #[serde(default)]
+    pub version: u32, // Schema version, bumped by registered migrations.
 }
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct Content {
     pub header: Header,
     pub settings: Option<toml::Value>,
+    // The service's own on-disk settings, before any `default/` layer or environment
+    // override was applied. `None` when the service has no layer of its own (e.g. a
+    // `Content` that only exists via a default); `push` and `set` populate it even
+    // before any file is written. `save` persists this layer, not the
+    // merged `settings` above, so layering never gets baked into a user's file.
+    #[serde(skip)]
+    pub user_settings: Option<toml::Value>,
+    // The settings exactly as parsed from whichever file actually produced
+    // `header.hash` -- a `default/` file when the service has no file of its own.
+    // Unlike `user_settings`, which stays `None` for a `default/`-only service,
+    // this is populated any time a file backs the entry, so `compute_hash`/`verify`
+    // keep comparing against what's really on disk instead of the merged/overridden
+    // view in `settings` (which env overrides and `default/` merging mutate in
+    // place regardless of whether the service has a user file of its own).
+    #[serde(skip)]
+    pub raw_settings: Option<toml::Value>,
+    // The extension the entry's own file actually has on disk, e.g. "yml" as
+    // opposed to `header.format.extension()`'s canonical "yaml". `None` until a
+    // file has been read (a freshly `push`ed `Content` has no file yet). `save`
+    // and `set` target this extension when it's known, instead of always
+    // canonicalizing to `header.format.extension()`, so a service loaded from
+    // `svc.yml` doesn't grow a second `svc.yaml` next to it on the next write.
+    #[serde(skip)]
+    pub source_extension: Option<String>,
 }
 
+/// A migration hook: given the settings stored at `from_version`, return their
+/// upgraded form. Registered per `(service_name, from_version)`.
+pub type Migration = fn(toml::Value) -> toml::Value;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SettingsManager {
-    pub path: String,
+    pub path: String, // Leading "~" or "~/" expanded to home; other paths, relative ones included, kept as given.
     pub settings: Vec<Content>,
+    // Prefix used to recognize environment-variable overrides, e.g. with the
+    // default prefix a service named "foo" reads overrides from
+    // `REST_SETTINGS_FOO__<dotted.key>`.
+    pub env_prefix: String,
+    #[serde(skip)]
+    pub migrations: HashMap<(String, u32), Migration>,
 }
 
+/// Separator between the service name and the dotted key in an environment override,
+/// e.g. `REST_SETTINGS_FOO__address.city`.
+const ENV_OVERRIDE_SEPARATOR: &str = "__";
+
 impl Default for SettingsManager {
     fn default() -> Self {
+        let path = SettingsManager::config_dir()
+            .join(env!("CARGO_PKG_NAME"))
+            .to_string_lossy()
+            .to_string();
         SettingsManager {
-            path: format!("~/.config/{}", env!("CARGO_PKG_NAME")),
+            path,
             settings: vec![],
+            env_prefix: "REST_SETTINGS_".to_string(),
+            migrations: HashMap::new(),
         }
     }
 }
 
 impl SettingsManager {
-    /// Create a new SettingsManager object with a proper initialization
+    /// Create a new SettingsManager, ready for `register_migration` to be called
+    /// before the first `load()`.
+    ///
+    /// Breaking change: earlier versions had `new` call `load` itself, so
+    /// `settings` was already populated on return. That's no longer true --
+    /// loading settings before any caller had a chance to register a migration
+    /// meant migrations could never run against the very first load, making
+    /// `register_migration` unreachable through the public API. Every existing
+    /// caller of `new` needs an explicit `load()` added afterward; until then,
+    /// `settings` stays empty.
     pub fn new(path: Option<String>) -> Self {
         let mut this = SettingsManager::default();
-        if path.is_some() {
-            this.path = path.unwrap();
+        if let Some(path) = path {
+            this.path = Self::expand_path(&path).to_string_lossy().to_string();
         }
         let _ = this.init();
-        this.load();
         return this;
     }
 
@@ -54,6 +149,34 @@ impl SettingsManager {
         std::fs::create_dir_all(self.get_default_folder())
     }
 
+    /// Resolve the platform configuration directory: `$XDG_CONFIG_HOME` when set,
+    /// otherwise the home directory joined with `.config`.
+    fn config_dir() -> PathBuf {
+        match std::env::var("XDG_CONFIG_HOME") {
+            Ok(xdg) if !xdg.is_empty() => PathBuf::from(xdg),
+            _ => Self::home_dir().join(".config"),
+        }
+    }
+
+    /// Resolve the current user's home directory across Linux/macOS/Windows.
+    fn home_dir() -> PathBuf {
+        let home = std::env::var("HOME")
+            .or_else(|_| std::env::var("USERPROFILE"))
+            .unwrap_or_else(|_| ".".to_string());
+        PathBuf::from(home)
+    }
+
+    /// Expand a leading `~` in `path` to the user's home directory.
+    fn expand_path(path: &str) -> PathBuf {
+        if path == "~" {
+            return Self::home_dir();
+        }
+        match path.strip_prefix("~/") {
+            Some(rest) => Self::home_dir().join(rest),
+            None => PathBuf::from(path),
+        }
+    }
+
     /// Get path that contains the default configuration files
     pub fn get_default_folder(&self) -> PathBuf {
         Path::new(&self.path).join("default")
@@ -71,48 +194,474 @@ impl SettingsManager {
         }
         content.header.date = chrono::Local::now().to_string();
         content.header.modified = false;
+        // This is the user's own entry (not one materialized from a `default/`
+        // file), so it's the layer `save` should persist and `verify` should
+        // compare future hashes against.
+        content.user_settings = content.settings.clone();
+        content.raw_settings = content.settings.clone();
+        content.header.hash = Self::compute_hash(&content);
+
+        self.settings.push(content);
+    }
+
+    /// Compute the canonical sha1 of a `Content`, as stored in `Header.hash`.
+    ///
+    /// The hash is taken over the TOML serialization of the content with
+    /// `header.hash` itself blanked out, mirroring how `push` first hashes
+    /// before the hash field is populated. It is always taken over the raw
+    /// `raw_settings` layer (falling back to `settings` when there is no
+    /// separate raw layer yet, e.g. before a layered `load` has run) rather
+    /// than `user_settings`, since `user_settings` is `None` for a
+    /// `default/`-only service even though such a service's `raw_settings`
+    /// is populated -- so merging in `default/` values or environment
+    /// overrides never makes an untouched file, default-only or not, look
+    /// like it was edited out-of-band.
+    fn compute_hash(content: &Content) -> String {
+        let for_hash = Content {
+            header: Header {
+                hash: String::new(),
+                ..content.header.clone()
+            },
+            settings: content
+                .raw_settings
+                .clone()
+                .or_else(|| content.settings.clone()),
+            user_settings: None,
+            raw_settings: None,
+            source_extension: None,
+        };
 
         let mut hasher = Sha1::new();
-        hasher.input(toml::to_string_pretty(&content).unwrap());
-        content.header.hash = hex::encode(hasher.result());
+        hasher.input(toml::to_string_pretty(&for_hash).unwrap());
+        hex::encode(hasher.result())
+    }
 
-        self.settings.push(content);
+    /// Return all entries whose recomputed hash no longer matches their stored
+    /// hash, i.e. settings files that were edited outside the service since
+    /// they were last saved. An entry upgraded by a migration during `load` is
+    /// returned as well until it is saved, since its header changed.
+    pub fn verify(&self) -> Vec<&Content> {
+        self.settings
+            .iter()
+            .filter(|content| Self::compute_hash(content) != content.header.hash)
+            .collect()
+    }
+
+    /// Get the value at a dotted key path (e.g. `address.city`) within a service's settings.
+    pub fn get(&self, name: &str, key: &str) -> Option<toml::Value> {
+        let content = self
+            .settings
+            .iter()
+            .find(|content| content.header.name == name)?;
+        let mut current = content.settings.as_ref()?;
+        for segment in key.split('.') {
+            current = current.get(segment)?;
+        }
+        Some(current.clone())
+    }
+
+    /// Set the value at a dotted key path (e.g. `address.city`) in a service's on-disk
+    /// file, preserving existing comments and key ordering.
+    ///
+    /// Unlike `save`, which rewrites a file from the in-memory `toml::Value`, this parses
+    /// the file with `toml_edit` and edits only the requested leaf in place. If the
+    /// service has no file of its own yet (it only exists via a `default/` file), one
+    /// is materialized here with just the key being set, the same way `save` would
+    /// persist a first user override -- the rest of the service's values keep coming
+    /// from `default/` until the user overrides them too.
+    pub fn set(&mut self, name: &str, key: &str, value: &str) -> Result<(), String> {
+        let header = self
+            .settings
+            .iter()
+            .find(|content| content.header.name == name)
+            .map(|content| content.header.clone())
+            .ok_or_else(|| format!("No settings found for: {}", name))?;
+        let source_extension = self
+            .settings
+            .iter()
+            .find(|content| content.header.name == name)
+            .and_then(|content| content.source_extension.clone());
+
+        let segments: Vec<&str> = key.split('.').collect();
+        if segments.iter().any(|segment| segment.is_empty()) {
+            return Err(format!("Empty key segment in: {}", key));
+        }
+
+        let extension =
+            source_extension.unwrap_or_else(|| header.format.extension().to_string());
+        let mut file_name = Path::new(&self.path).join(name);
+        file_name.set_extension(&extension);
+
+        let mut file_contents = String::new();
+        match File::open(&file_name).and_then(|mut file| file.read_to_string(&mut file_contents))
+        {
+            Ok(_) => {}
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                // No file of its own yet -- e.g. a service that only exists via a
+                // `default/` file. Seed one with an empty `settings` table rather
+                // than erroring, so `set` can materialize a service's first user
+                // override instead of requiring one to already exist.
+                let seed = Content {
+                    header: header.clone(),
+                    settings: Some(Self::empty_table()),
+                    user_settings: None,
+                    raw_settings: None,
+                    source_extension: None,
+                };
+                file_contents =
+                    toml::to_string_pretty(&seed).map_err(|error| format!("{:#?}", error))?;
+            }
+            Err(error) => return Err(format!("{:#?}", error)),
+        }
+
+        let mut document = file_contents
+            .parse::<toml_edit::Document>()
+            .map_err(|error| format!("{:#?}", error))?;
+
+        // Descend into `[settings]`, the sub-table `Content.settings` actually
+        // (de)serializes from, not the document root (which also holds `[header]`).
+        let settings_item = document
+            .as_table_mut()
+            .entry("settings")
+            .or_insert_with(|| toml_edit::Item::Table(toml_edit::Table::new()));
+        let mut table = settings_item
+            .as_table_mut()
+            .ok_or_else(|| "`settings` is not a table".to_string())?;
+
+        let (intermediate, leaf) = segments.split_at(segments.len() - 1);
+        for segment in intermediate {
+            let item = table
+                .entry(segment)
+                .or_insert_with(|| toml_edit::Item::Table(toml_edit::Table::new()));
+            table = item
+                .as_table_mut()
+                .ok_or_else(|| format!("Intermediate key is not a table: {}", segment))?;
+        }
+        table.insert(leaf[0], toml_edit::value(value));
+
+        let updated: Option<toml::Value> = toml::from_str::<Content>(&document.to_string())
+            .ok()
+            .and_then(|parsed| parsed.settings);
+
+        let new_hash = Self::compute_hash(&Content {
+            header: header.clone(),
+            settings: updated.clone(),
+            user_settings: None,
+            raw_settings: None,
+            source_extension: None,
+        });
+        document["header"]["hash"] = toml_edit::value(new_hash.clone());
+
+        let mut file = File::create(&file_name).map_err(|error| format!("{:#?}", error))?;
+        file.write_all(document.to_string().as_bytes())
+            .map_err(|error| format!("{:#?}", error))?;
+
+        if let Some(content) = self
+            .settings
+            .iter_mut()
+            .find(|content| content.header.name == name)
+        {
+            // Overlay the file's new values on the merged view and re-apply env
+            // overrides, so keys still coming from `default/` stay readable.
+            let merged = Self::merge_toml(
+                content.settings.take().unwrap_or_else(Self::empty_table),
+                updated.clone().unwrap_or_else(Self::empty_table),
+            );
+            content.settings = Some(Self::apply_env_overrides(&self.env_prefix, name, merged));
+            content.user_settings = updated.clone();
+            content.raw_settings = updated;
+            content.header.hash = new_hash;
+            content.source_extension = Some(extension);
+        }
+
+        Ok(())
+    }
+
+    /// Register a migration applied to a service's settings when its stored header
+    /// version equals `from_version`. On `load`, the migration chain for a `Content`
+    /// is followed version by version, bumping `header.version` each step and
+    /// flagging the content `modified` so a later `save` persists the upgrade.
+    pub fn register_migration(&mut self, name: &str, from_version: u32, migration: Migration) {
+        self.migrations.insert((name.to_string(), from_version), migration);
+    }
+
+    /// Run every registered migration applicable to `content`, in order.
+    fn migrate(&self, content: &mut Content) {
+        while let Some(migration) = self
+            .migrations
+            .get(&(content.header.name.clone(), content.header.version))
+        {
+            let settings = content.settings.take().unwrap_or_else(Self::empty_table);
+            content.settings = Some(migration(settings));
+            content.header.version += 1;
+            content.header.modified = true;
+        }
     }
 
     /// Load all settings available in the manager path
+    ///
+    /// Resolution is layered: every file under `default/` is loaded as the
+    /// base layer, matching files directly under `self.path` are merged on
+    /// top (user values winning), and finally any `REST_SETTINGS_<name>__<dotted.key>`
+    /// environment variable overrides the merged result. Any content stored at an
+    /// older schema version is upgraded via its registered migration chain first.
+    ///
+    /// Can be called more than once (e.g. after registering more migrations):
+    /// each reloaded service replaces its previous entry in `self.settings`
+    /// rather than being appended as a duplicate.
     pub fn load(&mut self) {
-        let files = std::fs::read_dir(&self.path).unwrap();
-        let files = files
-            .filter_map(Result::ok)
-            .filter(|file| match file.path().extension() {
-                Some(extension) => extension.to_str() == Some("toml"),
-                None => false,
-            });
+        let mut defaults = Self::load_dir(&self.get_default_folder());
+        let mut user_settings = Self::load_dir(Path::new(&self.path));
+
+        for content in defaults.iter_mut().chain(user_settings.iter_mut()) {
+            self.migrate(content);
+            // Snapshot the post-migration, pre-layering content now, before either a
+            // `default/` gets merged in or env overrides are applied below -- this is
+            // what `verify` must keep comparing against, including for a service that
+            // only exists via a `default/` file and whose `settings` is about to be
+            // overwritten with a merged/overridden view it never owned a file for.
+            content.raw_settings = content.settings.clone();
+        }
+
+        // Additionally snapshot the layer `save` persists: unlike `raw_settings`
+        // above, this only applies to entries that have their own file directly
+        // under `self.path` (as opposed to existing solely via a `default/` file).
+        for content in &mut user_settings {
+            content.user_settings = content.settings.clone();
+        }
+
+        for default in defaults {
+            match user_settings
+                .iter_mut()
+                .find(|content| content.header.name == default.header.name)
+            {
+                Some(content) => {
+                    content.settings = Some(Self::merge_toml(
+                        default.settings.unwrap_or_else(Self::empty_table),
+                        content.settings.take().unwrap_or_else(Self::empty_table),
+                    ));
+                }
+                None => user_settings.push(default),
+            }
+        }
 
-        for file in files {
+        for content in &mut user_settings {
+            let settings = content.settings.take().unwrap_or_else(Self::empty_table);
+            content.settings = Some(Self::apply_env_overrides(
+                &self.env_prefix,
+                &content.header.name,
+                settings,
+            ));
+        }
+
+        // Replace by name rather than blindly appending, so reloading (e.g. after
+        // registering a migration post-construction) doesn't leave a stale,
+        // unmigrated duplicate sitting alongside the fresh one.
+        for content in user_settings {
+            self.settings
+                .retain(|existing| existing.header.name != content.header.name);
+            self.settings.push(content);
+        }
+    }
+
+    /// Read every supported settings file directly under `dir` into `Content`s,
+    /// detecting out-of-band edits the same way a plain load would.
+    fn load_dir(dir: &Path) -> Vec<Content> {
+        let mut loaded = vec![];
+
+        let files = match std::fs::read_dir(dir) {
+            Ok(files) => files,
+            Err(_) => return loaded,
+        };
+        let files = files.filter_map(Result::ok).filter_map(|file| {
+            let extension = file.path().extension()?.to_str()?.to_string();
+            FileFormat::from_extension(&extension).map(|format| (file, format, extension))
+        });
+
+        for (file, format, extension) in files {
             let mut contents = String::new();
             let mut file = File::open(file.path()).unwrap();
             file.read_to_string(&mut contents).unwrap();
-            self.settings.push(toml::from_str(&contents).unwrap())
+            let mut content = Self::deserialize_content(&contents, format);
+            content.header.format = format;
+            // "yml" and "yaml" both map to `FileFormat::Yaml`; remember which one
+            // this file actually used so a later `save`/`set` writes back to it
+            // instead of canonicalizing to `format.extension()` and leaving a
+            // second, stale file with the original extension behind.
+            content.source_extension = Some(extension);
+            if Self::compute_hash(&content) != content.header.hash {
+                content.header.modified = true;
+                println!(
+                    "Settings file modified outside of the service: {}",
+                    content.header.name
+                );
+            }
+            loaded.push(content);
+        }
+
+        loaded
+    }
+
+    /// An empty TOML table, used as the neutral element when merging/overriding.
+    fn empty_table() -> toml::Value {
+        toml::Value::Table(toml::value::Table::new())
+    }
+
+    /// Merge two TOML values key-by-key, with `overlay` winning on conflicts.
+    /// Nested tables are merged recursively; any other value is simply replaced.
+    fn merge_toml(base: toml::Value, overlay: toml::Value) -> toml::Value {
+        match (base, overlay) {
+            (toml::Value::Table(mut base_table), toml::Value::Table(overlay_table)) => {
+                for (key, value) in overlay_table {
+                    let merged = match base_table.remove(&key) {
+                        Some(base_value) => Self::merge_toml(base_value, value),
+                        None => value,
+                    };
+                    base_table.insert(key, merged);
+                }
+                toml::Value::Table(base_table)
+            }
+            (_, overlay) => overlay,
+        }
+    }
+
+    /// Apply `<prefix><NAME>__<dotted.key>` environment variable overrides onto `settings`.
+    fn apply_env_overrides(prefix: &str, name: &str, mut settings: toml::Value) -> toml::Value {
+        let var_prefix = format!(
+            "{}{}{}",
+            prefix,
+            name.to_uppercase(),
+            ENV_OVERRIDE_SEPARATOR
+        );
+
+        for (key, value) in std::env::vars() {
+            if let Some(dotted_key) = key.strip_prefix(&var_prefix) {
+                let segments: Vec<&str> = dotted_key.split('.').collect();
+                Self::set_nested(&mut settings, &segments, toml::Value::String(value));
+            }
+        }
+
+        settings
+    }
+
+    /// Walk/create intermediate tables along `segments`, setting the leaf to `value`.
+    fn set_nested(target: &mut toml::Value, segments: &[&str], value: toml::Value) {
+        if segments.is_empty() || segments[0].is_empty() {
+            return;
+        }
+        if !target.is_table() {
+            *target = Self::empty_table();
+        }
+        let table = target.as_table_mut().unwrap();
+        if segments.len() == 1 {
+            table.insert(segments[0].to_string(), value);
+        } else {
+            let entry = table
+                .entry(segments[0].to_string())
+                .or_insert_with(Self::empty_table);
+            Self::set_nested(entry, &segments[1..], value);
         }
     }
 
     /// Save all settings available in the manager path
-    pub fn save(&self) {
-        for setting in &self.settings {
-            // Open if the file exist, otherwise create it
-            let mut file_name = Path::new(&self.path).join(&setting.header.name);
-            file_name.set_extension("toml");
+    ///
+    /// Each file is written to a temporary file in the same directory, fsynced, then
+    /// atomically renamed over the target, so a crash mid-write can't leave a
+    /// truncated or corrupted file behind. The written file is restricted to
+    /// user-only permissions since settings may hold credentials.
+    ///
+    /// Persists each service's own `user_settings` layer rather than the merged
+    /// `settings` view, so a service that only ever had its `default/` values (plus
+    /// whatever environment overrides happened to be set) never has those baked
+    /// into a file a human will read back as "what I configured". A service with
+    /// no `user_settings` layer at all (it exists only via a `default/` file and
+    /// was never pushed or edited) is skipped entirely, so it keeps tracking
+    /// `default/` instead of being permanently shadowed by a materialized copy.
+    ///
+    /// Recomputes `header.hash` over the content about to be written (the same way
+    /// `set` does) before serializing, rather than trusting whatever hash is already
+    /// sitting in memory: a migration (or anything else that mutates `user_settings`
+    /// without going through `set`) leaves the in-memory hash stale relative to the
+    /// content it now describes, and writing it verbatim would persist a file that
+    /// immediately fails its own `verify`.
+    pub fn save(&mut self) -> std::io::Result<()> {
+        for setting in &mut self.settings {
+            let user_settings = match &setting.user_settings {
+                Some(user_settings) => user_settings.clone(),
+                None => continue,
+            };
 
-            let mut file = File::create(file_name).unwrap_or_else(|error| {
-                panic!("{:#?}", error);
+            setting.header.hash = Self::compute_hash(&Content {
+                header: setting.header.clone(),
+                settings: Some(user_settings.clone()),
+                user_settings: None,
+                raw_settings: None,
+                source_extension: None,
             });
 
-            let _ = file.write_all(
-                toml::to_string_pretty(&setting)
-                    .unwrap_or_else(|error| panic!("{:#?}", error))
-                    .as_bytes(),
-            );
+            // Reuse the extension the file was actually loaded with (e.g. "yml"),
+            // falling back to the format's canonical one only when there's no file
+            // yet, so this doesn't grow a second file alongside the original.
+            let extension = setting
+                .source_extension
+                .clone()
+                .unwrap_or_else(|| setting.header.format.extension().to_string());
+            let mut file_name = Path::new(&self.path).join(&setting.header.name);
+            file_name.set_extension(extension);
+
+            let mut tmp_file_name = file_name.clone().into_os_string();
+            tmp_file_name.push(".tmp");
+            let tmp_file_name = PathBuf::from(tmp_file_name);
+
+            let for_save = Content {
+                header: setting.header.clone(),
+                settings: Some(user_settings),
+                user_settings: None,
+                raw_settings: None,
+                source_extension: None,
+            };
+            let contents = Self::serialize_content(&for_save, setting.header.format)
+                .map_err(|error| std::io::Error::new(std::io::ErrorKind::Other, error))?;
+
+            {
+                let mut file = File::create(&tmp_file_name)?;
+                file.write_all(contents.as_bytes())?;
+                file.sync_all()?;
+            }
+
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(&tmp_file_name, std::fs::Permissions::from_mode(0o600))?;
+            }
+
+            std::fs::rename(&tmp_file_name, &file_name)?;
+        }
+
+        Ok(())
+    }
+
+    /// Serialize a `Content` using the format it was loaded from (or defaults to).
+    fn serialize_content(content: &Content, format: FileFormat) -> Result<String, String> {
+        match format {
+            FileFormat::Toml => {
+                toml::to_string_pretty(content).map_err(|error| format!("{:#?}", error))
+            }
+            FileFormat::Json => {
+                serde_json::to_string_pretty(content).map_err(|error| format!("{:#?}", error))
+            }
+            FileFormat::Yaml => {
+                serde_yaml::to_string(content).map_err(|error| format!("{:#?}", error))
+            }
+        }
+    }
+
+    /// Deserialize a `Content` from the textual representation of the given format.
+    fn deserialize_content(contents: &str, format: FileFormat) -> Content {
+        match format {
+            FileFormat::Toml => toml::from_str(contents).unwrap(),
+            FileFormat::Json => serde_json::from_str(contents).unwrap(),
+            FileFormat::Yaml => serde_yaml::from_str(contents).unwrap(),
         }
     }
 }
@@ -125,6 +674,23 @@ mod tests {
         std::env::temp_dir().to_str().unwrap().to_string()
     }
 
+    /// A fresh, test-private directory under the system temp dir, so parallel
+    /// tests don't trip over each other's settings files.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rest-settings-service-test-{}", name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_content_file(path: &Path, name: &str, settings: toml::value::Table) {
+        let mut content = Content::default();
+        content.header.name = name.to_string();
+        content.settings = Some(toml::Value::Table(settings));
+        content.header.hash = SettingsManager::compute_hash(&content);
+        std::fs::write(path, toml::to_string_pretty(&content).unwrap()).unwrap();
+    }
+
     #[test]
     fn simple() {
         println!("Test safe..");
@@ -163,7 +729,7 @@ mod tests {
         content.header.name = "test".to_string();
         content.settings = Some(toml_example);
         settings_manager.push(content);
-        settings_manager.save();
+        settings_manager.save().unwrap();
 
         // Check file
         let content_toml_string =
@@ -185,7 +751,8 @@ mod tests {
     }
 
     fn load() {
-        let settings_manager = SettingsManager::new(Some(create_path()));
+        let mut settings_manager = SettingsManager::new(Some(create_path()));
+        settings_manager.load();
         let item = settings_manager
             .settings
             .iter()
@@ -199,4 +766,410 @@ mod tests {
         assert_eq!(settings["address"]["city"].as_str().unwrap(), "London");
         assert_eq!(settings["phones"][1].as_str().unwrap(), "+44 2345678");
     }
+
+    #[test]
+    fn set_preserves_comments_and_round_trips() {
+        let dir = test_dir("set-preserves-comments");
+
+        let mut settings_manager = SettingsManager::new(Some(dir.to_str().unwrap().to_string()));
+        settings_manager.settings.clear();
+
+        let mut table = toml::value::Table::new();
+        table.insert("name".to_string(), toml::Value::String("John".to_string()));
+        let mut content = Content::default();
+        content.header.name = "test".to_string();
+        content.settings = Some(toml::Value::Table(table));
+        settings_manager.push(content);
+        settings_manager.save().unwrap();
+
+        let mut file_name = Path::new(&settings_manager.path).join("test");
+        file_name.set_extension("toml");
+
+        // Hand-add a comment the way a human operator would, to confirm `set`
+        // doesn't clobber it by rewriting the whole file.
+        let file_contents = std::fs::read_to_string(&file_name).unwrap();
+        let file_contents = file_contents.replacen("[settings]", "# kept by a human\n[settings]", 1);
+        std::fs::write(&file_name, &file_contents).unwrap();
+
+        settings_manager
+            .set("test", "address.city", "London")
+            .unwrap();
+
+        let saved = std::fs::read_to_string(&file_name).unwrap();
+        assert!(saved.contains("# kept by a human"));
+
+        assert_eq!(
+            settings_manager
+                .get("test", "address.city")
+                .unwrap()
+                .as_str()
+                .unwrap(),
+            "London"
+        );
+
+        // `set` must refresh the stored hash, the same way `push`/`save` do, so
+        // its own write isn't later mistaken for an out-of-band edit.
+        assert!(settings_manager.verify().is_empty());
+
+        let mut reloaded = SettingsManager::new(Some(dir.to_str().unwrap().to_string()));
+        reloaded.load();
+        assert!(reloaded.verify().is_empty());
+        assert!(
+            !reloaded
+                .settings
+                .iter()
+                .find(|content| content.header.name == "test")
+                .unwrap()
+                .header
+                .modified
+        );
+    }
+
+    #[test]
+    fn set_materializes_a_first_user_override_for_a_default_only_service() {
+        let dir = test_dir("set-creates-first-override");
+        std::fs::create_dir_all(dir.join("default")).unwrap();
+
+        let mut default_table = toml::value::Table::new();
+        default_table.insert(
+            "city".to_string(),
+            toml::Value::String("default-city".to_string()),
+        );
+        write_content_file(&dir.join("default").join("svc.toml"), "svc", default_table);
+
+        let mut settings_manager = SettingsManager::new(Some(dir.to_str().unwrap().to_string()));
+        settings_manager.load();
+        assert!(
+            !dir.join("svc.toml").exists(),
+            "sanity check: svc should only exist via default/ before set()"
+        );
+
+        settings_manager.set("svc", "city", "user-city").unwrap();
+
+        assert!(
+            dir.join("svc.toml").exists(),
+            "set() must materialize a user file when one doesn't exist yet"
+        );
+        assert_eq!(
+            settings_manager.get("svc", "city").unwrap().as_str().unwrap(),
+            "user-city"
+        );
+        assert!(settings_manager.verify().is_empty());
+
+        let mut reloaded = SettingsManager::new(Some(dir.to_str().unwrap().to_string()));
+        reloaded.load();
+        assert_eq!(
+            reloaded.get("svc", "city").unwrap().as_str().unwrap(),
+            "user-city"
+        );
+        assert!(reloaded.verify().is_empty());
+    }
+
+    #[test]
+    fn layered_load_merges_but_save_only_persists_user_layer() {
+        let dir = test_dir("layered-load");
+        std::fs::create_dir_all(dir.join("default")).unwrap();
+
+        let mut default_table = toml::value::Table::new();
+        default_table.insert(
+            "host".to_string(),
+            toml::Value::String("default-host".to_string()),
+        );
+        default_table.insert("port".to_string(), toml::Value::Integer(1));
+        write_content_file(&dir.join("default").join("svc.toml"), "svc", default_table);
+
+        let mut user_table = toml::value::Table::new();
+        user_table.insert("port".to_string(), toml::Value::Integer(2));
+        write_content_file(&dir.join("svc.toml"), "svc", user_table);
+
+        let mut settings_manager = SettingsManager::new(Some(dir.to_str().unwrap().to_string()));
+        settings_manager.load();
+
+        // Reads see the merged view: the default's `host` plus the user's own `port`.
+        assert_eq!(
+            settings_manager.get("svc", "host").unwrap().as_str().unwrap(),
+            "default-host"
+        );
+        assert_eq!(
+            settings_manager
+                .get("svc", "port")
+                .unwrap()
+                .as_integer()
+                .unwrap(),
+            2
+        );
+
+        // Layering in a default (and, if set, an env override) must not make an
+        // untouched user file look like it was edited out-of-band.
+        assert!(settings_manager.verify().is_empty());
+
+        settings_manager.save().unwrap();
+
+        let raw_after_save = std::fs::read_to_string(dir.join("svc.toml")).unwrap();
+        let raw_content: Content = toml::from_str(&raw_after_save).unwrap();
+        let raw_settings = raw_content.settings.unwrap();
+        assert!(
+            raw_settings.get("host").is_none(),
+            "save() must not bake the default layer into the user's own file"
+        );
+        assert_eq!(raw_settings.get("port").unwrap().as_integer().unwrap(), 2);
+    }
+
+    #[test]
+    fn migration_runs_on_load_and_reload_replaces_rather_than_duplicates() {
+        let dir = test_dir("migration");
+
+        let mut table = toml::value::Table::new();
+        table.insert("name".to_string(), toml::Value::String("svc".to_string()));
+        write_content_file(&dir.join("svc.toml"), "svc", table);
+
+        let mut settings_manager = SettingsManager::new(Some(dir.to_str().unwrap().to_string()));
+        settings_manager.register_migration("svc", 0, |mut settings| {
+            if let Some(table) = settings.as_table_mut() {
+                table.insert("migrated".to_string(), toml::Value::Boolean(true));
+            }
+            settings
+        });
+        settings_manager.load();
+
+        assert_eq!(settings_manager.settings.len(), 1);
+        let content = settings_manager
+            .settings
+            .iter()
+            .find(|content| content.header.name == "svc")
+            .unwrap();
+        assert_eq!(content.header.version, 1);
+        assert!(
+            content
+                .settings
+                .as_ref()
+                .unwrap()
+                .get("migrated")
+                .unwrap()
+                .as_bool()
+                .unwrap()
+        );
+
+        // Reloading (e.g. after registering further migrations) must replace the
+        // existing entry rather than append a stale, unmigrated duplicate.
+        settings_manager.load();
+        assert_eq!(settings_manager.settings.len(), 1);
+    }
+
+    #[test]
+    fn save_after_migration_persists_a_hash_matching_the_written_content() {
+        let dir = test_dir("migration-then-save");
+
+        let mut table = toml::value::Table::new();
+        table.insert("name".to_string(), toml::Value::String("svc".to_string()));
+        write_content_file(&dir.join("svc.toml"), "svc", table);
+
+        let mut settings_manager = SettingsManager::new(Some(dir.to_str().unwrap().to_string()));
+        settings_manager.register_migration("svc", 0, |mut settings| {
+            if let Some(table) = settings.as_table_mut() {
+                table.insert("migrated".to_string(), toml::Value::Boolean(true));
+            }
+            settings
+        });
+        settings_manager.load();
+        settings_manager.save().unwrap();
+
+        // A migration mutates `settings`/`user_settings` without going through
+        // `set`, which is the only other place that refreshes `header.hash`; `save`
+        // must recompute it too, or the file it just wrote fails its own `verify`
+        // the moment it's reloaded.
+        assert!(settings_manager.verify().is_empty());
+
+        let mut reloaded = SettingsManager::new(Some(dir.to_str().unwrap().to_string()));
+        reloaded.load();
+        assert!(reloaded.verify().is_empty());
+    }
+
+    #[test]
+    fn save_does_not_materialize_a_file_for_default_only_content() {
+        let dir = test_dir("default-only");
+        std::fs::create_dir_all(dir.join("default")).unwrap();
+
+        let mut default_table = toml::value::Table::new();
+        default_table.insert(
+            "host".to_string(),
+            toml::Value::String("default-host".to_string()),
+        );
+        write_content_file(&dir.join("default").join("svc.toml"), "svc", default_table);
+
+        let mut settings_manager = SettingsManager::new(Some(dir.to_str().unwrap().to_string()));
+        settings_manager.load();
+
+        assert_eq!(
+            settings_manager.get("svc", "host").unwrap().as_str().unwrap(),
+            "default-host"
+        );
+
+        settings_manager.save().unwrap();
+
+        assert!(
+            !dir.join("svc.toml").exists(),
+            "save() must not shadow an untouched default/ file with a materialized copy"
+        );
+    }
+
+    #[test]
+    fn env_override_on_default_only_content_does_not_look_like_an_out_of_band_edit() {
+        let dir = test_dir("default-only-env-override");
+        std::fs::create_dir_all(dir.join("default")).unwrap();
+
+        let mut default_table = toml::value::Table::new();
+        default_table.insert(
+            "city".to_string(),
+            toml::Value::String("default-city".to_string()),
+        );
+        write_content_file(&dir.join("default").join("svc.toml"), "svc", default_table);
+
+        std::env::set_var("REST_SETTINGS_SVC__city", "env-city");
+
+        let mut settings_manager = SettingsManager::new(Some(dir.to_str().unwrap().to_string()));
+        settings_manager.load();
+
+        assert_eq!(
+            settings_manager.get("svc", "city").unwrap().as_str().unwrap(),
+            "env-city"
+        );
+
+        // A service that exists only via `default/`, with its value overridden
+        // purely by an environment variable and no user file of its own, is the
+        // primary env-override use case (ship defaults, override via env). Neither
+        // the `default/` merge nor the env override touched the file on disk, so it
+        // must not be reported as edited out-of-band.
+        assert!(settings_manager.verify().is_empty());
+
+        std::env::remove_var("REST_SETTINGS_SVC__city");
+    }
+
+    #[test]
+    fn env_override_wins_over_default_and_user_values() {
+        let dir = test_dir("env-override");
+        std::fs::create_dir_all(dir.join("default")).unwrap();
+
+        let mut default_table = toml::value::Table::new();
+        default_table.insert(
+            "city".to_string(),
+            toml::Value::String("default-city".to_string()),
+        );
+        write_content_file(&dir.join("default").join("svc.toml"), "svc", default_table);
+
+        let mut user_table = toml::value::Table::new();
+        user_table.insert(
+            "city".to_string(),
+            toml::Value::String("user-city".to_string()),
+        );
+        write_content_file(&dir.join("svc.toml"), "svc", user_table);
+
+        std::env::set_var("REST_SETTINGS_SVC__city", "env-city");
+
+        let mut settings_manager = SettingsManager::new(Some(dir.to_str().unwrap().to_string()));
+        settings_manager.load();
+
+        assert_eq!(
+            settings_manager.get("svc", "city").unwrap().as_str().unwrap(),
+            "env-city"
+        );
+
+        std::env::remove_var("REST_SETTINGS_SVC__city");
+    }
+
+    #[test]
+    fn json_and_yaml_round_trip() {
+        for (format, extension) in [(FileFormat::Json, "json"), (FileFormat::Yaml, "yaml")] {
+            let dir = test_dir(&format!("round-trip-{}", extension));
+
+            let mut settings_manager =
+                SettingsManager::new(Some(dir.to_str().unwrap().to_string()));
+
+            let mut table = toml::value::Table::new();
+            table.insert("city".to_string(), toml::Value::String("London".to_string()));
+            let mut content = Content::default();
+            content.header.name = "svc".to_string();
+            content.header.format = format;
+            content.settings = Some(toml::Value::Table(table));
+            settings_manager.push(content);
+            settings_manager.save().unwrap();
+
+            let mut file_name = dir.join("svc");
+            file_name.set_extension(extension);
+            assert!(file_name.exists());
+
+            let mut reloaded = SettingsManager::new(Some(dir.to_str().unwrap().to_string()));
+            reloaded.load();
+            assert_eq!(
+                reloaded.get("svc", "city").unwrap().as_str().unwrap(),
+                "London"
+            );
+            assert!(reloaded.verify().is_empty());
+        }
+    }
+
+    #[test]
+    fn save_after_loading_a_yml_file_does_not_grow_a_yaml_twin() {
+        let dir = test_dir("yml-yaml-twin");
+
+        let mut table = toml::value::Table::new();
+        table.insert("city".to_string(), toml::Value::String("London".to_string()));
+        let mut content = Content::default();
+        content.header.name = "svc".to_string();
+        content.header.format = FileFormat::Yaml;
+        content.settings = Some(toml::Value::Table(table));
+        content.header.hash = SettingsManager::compute_hash(&content);
+        std::fs::write(
+            dir.join("svc.yml"),
+            serde_yaml::to_string(&content).unwrap(),
+        )
+        .unwrap();
+
+        let mut settings_manager = SettingsManager::new(Some(dir.to_str().unwrap().to_string()));
+        settings_manager.load();
+        settings_manager.save().unwrap();
+
+        assert!(
+            dir.join("svc.yml").exists(),
+            "save() must write back to the file's original extension"
+        );
+        assert!(
+            !dir.join("svc.yaml").exists(),
+            "save() must not leave a second, canonically-named file behind"
+        );
+    }
+
+    #[test]
+    fn default_path_prefers_xdg_config_home_over_literal_tilde() {
+        let dir = test_dir("xdg-config-home");
+        std::env::set_var("XDG_CONFIG_HOME", &dir);
+
+        // No explicit path: falls back to `SettingsManager::default()`, which
+        // resolves the config directory via `config_dir()` rather than ever
+        // writing a literal "~".
+        let settings_manager = SettingsManager::new(None);
+        assert_eq!(
+            settings_manager.path,
+            dir.join(env!("CARGO_PKG_NAME")).to_str().unwrap()
+        );
+        assert!(!settings_manager.path.contains('~'));
+
+        std::env::remove_var("XDG_CONFIG_HOME");
+    }
+
+    #[test]
+    fn expand_path_resolves_leading_tilde_against_home() {
+        assert_eq!(
+            SettingsManager::expand_path("~/rest-settings-service"),
+            SettingsManager::home_dir().join("rest-settings-service")
+        );
+        assert_eq!(
+            SettingsManager::expand_path("~"),
+            SettingsManager::home_dir()
+        );
+        assert_eq!(
+            SettingsManager::expand_path("/etc/rest-settings-service"),
+            PathBuf::from("/etc/rest-settings-service")
+        );
+    }
 }
\ No newline at end of file